@@ -0,0 +1,62 @@
+//! Command line arguments for elf2tab.
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "elf2tab", about = "Convert ELF files to Tock Application Bundles (TABs).")]
+pub struct Opt {
+    /// Produce a deterministic output file, skipping anything (like a
+    /// timestamp) that would otherwise make two builds of the same inputs
+    /// differ.
+    #[structopt(short = "d", long = "deterministic")]
+    pub deterministic: bool,
+
+    /// Output verbose information about the sections and headers that were
+    /// created while converting the ELF file.
+    #[structopt(short = "v", long = "verbose")]
+    pub verbose: bool,
+
+    /// Name of the application to write into the TAB metadata.
+    #[structopt(short = "n", long = "package-name")]
+    pub package_name: Option<String>,
+
+    /// Size in bytes to reserve for the application's stack. If not
+    /// provided, falls back to a symbol-derived size when `--auto-reserve`
+    /// is passed, and to a built-in default otherwise.
+    #[structopt(long = "stack")]
+    pub stack_size: Option<u32>,
+
+    /// Size in bytes to reserve for the application's heap. If not provided,
+    /// falls back to a symbol-derived size when `--auto-reserve` is passed,
+    /// and to a built-in default otherwise.
+    #[structopt(long = "app-heap")]
+    pub app_heap_size: Option<u32>,
+
+    /// Size in bytes to reserve for the kernel's per-app heap. If not
+    /// provided, falls back to a symbol-derived size when `--auto-reserve`
+    /// is passed, and to a built-in default otherwise.
+    #[structopt(long = "kernel-heap")]
+    pub kernel_heap_size: Option<u32>,
+
+    /// Derive any of `--stack`, `--app-heap`, or `--kernel-heap` that was not
+    /// explicitly passed from well-known symbols in the ELF file's symbol
+    /// table instead of from a built-in default, so a single linker script
+    /// can be the source of truth for these sizes.
+    #[structopt(long = "auto-reserve")]
+    pub auto_reserve: bool,
+
+    /// Fix the size of the protected region (which holds the TBF headers) to
+    /// this many bytes. If not provided, the protected region is sized to
+    /// exactly fit the generated headers.
+    #[structopt(long = "protected-region-size")]
+    pub protected_region_size: Option<u32>,
+
+    /// Name of the output .tab file to create.
+    #[structopt(short = "o", long = "output", parse(from_os_str), default_value = "TockApp.tab")]
+    pub output: PathBuf,
+
+    /// The ELF files to package.
+    #[structopt(parse(from_os_str), required = true)]
+    pub input: Vec<PathBuf>,
+}
@@ -0,0 +1,158 @@
+//! Generation of Tock Binary Format (TBF) headers.
+//!
+//! See https://github.com/tock/tock/blob/master/doc/TockBinaryFormat.md for
+//! the on-disk layout this module produces.
+
+use std::fmt;
+use std::io;
+use std::io::{Cursor, Write};
+use std::mem;
+
+const TBF_HEADER_TYPE_MAIN: u16 = 1;
+const TBF_HEADER_TYPE_WRITEABLE_FLASH_REGIONS: u16 = 3;
+const TBF_HEADER_TYPE_PACKAGE_NAME: u16 = 4;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TbfHeaderMain {
+    init_fn_offset: u32,
+    protected_size: u32,
+    minimum_ram_size: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TbfHeaderWriteableFlashRegion {
+    offset: u32,
+    size: u32,
+}
+
+/// Accumulates the fields of a TBF header as they become known during
+/// `elf_to_tbf`, then serializes them to the binary TLV layout the Tock
+/// kernel expects.
+#[derive(Clone, Debug, Default)]
+pub struct TbfHeader {
+    total_size: u32,
+    main: TbfHeaderMain,
+    package_name: String,
+    wfrs: Vec<TbfHeaderWriteableFlashRegion>,
+}
+
+impl TbfHeader {
+    pub fn new() -> TbfHeader {
+        TbfHeader::default()
+    }
+
+    /// Fill in the fields that are known before we have placed any sections,
+    /// and return the number of bytes the generated header will occupy.
+    pub fn create(
+        &mut self,
+        minimum_ram_size: u32,
+        writeable_flash_regions_count: usize,
+        package_name: String,
+    ) -> usize {
+        self.main.minimum_ram_size = minimum_ram_size;
+        self.package_name = package_name;
+        self.wfrs = vec![TbfHeaderWriteableFlashRegion::default(); writeable_flash_regions_count];
+
+        self.size()
+    }
+
+    /// Total size, in bytes, of the header once generated. Must stay in sync
+    /// with the fields written by `generate`.
+    pub fn size(&self) -> usize {
+        let base = mem::size_of::<u16>() * 2 + mem::size_of::<u32>() * 3;
+        let main_tlv = mem::size_of::<u16>() * 2 + mem::size_of::<u32>() * 3;
+        let package_name_tlv =
+            align4!(mem::size_of::<u16>() * 2 + self.package_name.as_bytes().len());
+        let wfr_tlv = self
+            .wfrs
+            .len()
+            * (mem::size_of::<u16>() * 2 + mem::size_of::<u32>() * 2);
+
+        base + main_tlv + package_name_tlv + wfr_tlv
+    }
+
+    pub fn set_protected_size(&mut self, protected_size: u32) {
+        self.main.protected_size = protected_size;
+    }
+
+    pub fn set_init_fn_offset(&mut self, init_fn_offset: u32) {
+        self.main.init_fn_offset = init_fn_offset;
+    }
+
+    pub fn set_total_size(&mut self, total_size: u32) {
+        self.total_size = total_size;
+    }
+
+    /// Record the offset/size of the next unused writeable flash region slot
+    /// reserved by `create`.
+    pub fn set_writeable_flash_region_values(&mut self, offset: u32, size: u32) {
+        if let Some(wfr) = self.wfrs.iter_mut().find(|wfr| wfr.size == 0) {
+            wfr.offset = offset;
+            wfr.size = size;
+        }
+    }
+
+    /// Serialize the header to its binary TLV form, computing the checksum
+    /// over the finished bytes.
+    pub fn generate(&self) -> io::Result<Cursor<Vec<u8>>> {
+        let header_size = self.size();
+        let mut binary = Vec::with_capacity(header_size);
+
+        binary.write_all(&2u16.to_le_bytes())?; // version
+        binary.write_all(&(header_size as u16).to_le_bytes())?;
+        binary.write_all(&self.total_size.to_le_bytes())?;
+        binary.write_all(&1u32.to_le_bytes())?; // flags: enabled
+        binary.write_all(&0u32.to_le_bytes())?; // checksum placeholder
+
+        binary.write_all(&TBF_HEADER_TYPE_MAIN.to_le_bytes())?;
+        binary.write_all(&12u16.to_le_bytes())?;
+        binary.write_all(&self.main.init_fn_offset.to_le_bytes())?;
+        binary.write_all(&self.main.protected_size.to_le_bytes())?;
+        binary.write_all(&self.main.minimum_ram_size.to_le_bytes())?;
+
+        let name_bytes = self.package_name.as_bytes();
+        binary.write_all(&TBF_HEADER_TYPE_PACKAGE_NAME.to_le_bytes())?;
+        binary.write_all(&(name_bytes.len() as u16).to_le_bytes())?;
+        binary.write_all(name_bytes)?;
+        let name_pad = align4!(name_bytes.len()) - name_bytes.len();
+        binary.write_all(&vec![0u8; name_pad])?;
+
+        for wfr in &self.wfrs {
+            binary.write_all(&TBF_HEADER_TYPE_WRITEABLE_FLASH_REGIONS.to_le_bytes())?;
+            binary.write_all(&8u16.to_le_bytes())?;
+            binary.write_all(&wfr.offset.to_le_bytes())?;
+            binary.write_all(&wfr.size.to_le_bytes())?;
+        }
+
+        // Compute the checksum as the XOR of every 4-byte little-endian word
+        // in the header, with the checksum field itself treated as zero.
+        let mut checksum: u32 = 0;
+        for word in binary.chunks(4) {
+            let mut word_bytes = [0u8; 4];
+            word_bytes[..word.len()].copy_from_slice(word);
+            checksum ^= u32::from_le_bytes(word_bytes);
+        }
+        binary[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+        Ok(Cursor::new(binary))
+    }
+}
+
+impl fmt::Display for TbfHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "TBF Header:")?;
+        writeln!(f, "  total_size:        {} ({0:#x})", self.total_size)?;
+        writeln!(f, "  init_fn_offset:    {}", self.main.init_fn_offset)?;
+        writeln!(f, "  protected_size:    {}", self.main.protected_size)?;
+        writeln!(f, "  minimum_ram_size:  {}", self.main.minimum_ram_size)?;
+        writeln!(f, "  package_name:      {:?}", self.package_name)?;
+        for (i, wfr) in self.wfrs.iter().enumerate() {
+            writeln!(
+                f,
+                "  writeable_flash_region[{}]: offset={} size={}",
+                i, wfr.offset, wfr.size
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! The on-flash layout of a generated TBF binary.
+//!
+//! `elf_to_tbf` works in two passes: a *reserve* pass walks the ELF file and
+//! computes the offset and size of every piece that will end up in the
+//! output file (the protected region, each copied section, and the
+//! relocation blob, plus the trailing padding), recording them here. A
+//! *write* pass then emits bytes strictly in the order they were reserved.
+//! Keeping the passes separate means a new field (or a bug in an existing
+//! one) shows up as a wrong offset in the `Layout` itself, rather than as a
+//! silently-corrupt binary.
+
+use std::fmt;
+
+/// The offset and size, in the final output file, of one ELF section that
+/// was copied into the app binary.
+#[derive(Clone, Debug)]
+pub struct SectionLayout {
+    pub name: String,
+    /// Index into the ELF file's section table, so the write pass can fetch
+    /// this exact section's data again without relying on name lookups
+    /// (names are not guaranteed unique).
+    pub section_index: usize,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// The complete reserved layout of a TBF app binary, in file order.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    pub protected_region_size: usize,
+    pub header_size: usize,
+    pub sections: Vec<SectionLayout>,
+    pub relocation_offset: usize,
+    pub relocation_size: usize,
+    pub init_fn_offset: u32,
+    pub pad: usize,
+    pub total_size: usize,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "  {:<20} offset: {:#08x}  size: {:#x}",
+            "protected_region", 0, self.protected_region_size
+        )?;
+        for section in &self.sections {
+            writeln!(
+                f,
+                "  {:<20} offset: {:#08x}  size: {:#x}",
+                section.name, section.offset, section.size
+            )?;
+        }
+        writeln!(
+            f,
+            "  {:<20} offset: {:#08x}  size: {:#x}",
+            "relocations", self.relocation_offset, self.relocation_size
+        )?;
+        writeln!(f, "  {:<20} offset: {:#x}", "init_fn", self.init_fn_offset)?;
+        writeln!(f, "  {:<20} size: {:#x}", "padding", self.pad)?;
+        writeln!(f, "  {:<20} size: {:#x}", "total", self.total_size)
+    }
+}
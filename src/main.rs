@@ -1,20 +1,31 @@
 extern crate chrono;
-extern crate elf;
+extern crate object;
 extern crate tar;
 #[macro_use]
 extern crate structopt;
 
 use std::cmp;
+use std::convert::TryFrom;
 use std::fmt::Write as fmtwrite;
 use std::fs;
 use std::io;
 use std::io::{Seek, Write};
 use std::mem;
 
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SectionFlags, SegmentFlags};
+
+/// Built-in fallback reservations, used when neither an explicit flag nor
+/// (with `--auto-reserve`) a matching ELF symbol is available.
+const DEFAULT_STACK_SIZE: u32 = 2048;
+const DEFAULT_APP_HEAP_SIZE: u32 = 1024;
+const DEFAULT_KERNEL_HEAP_SIZE: u32 = 1024;
+
 #[macro_use]
 mod util;
 mod cmdline;
 mod header;
+mod layout;
+use layout::{Layout, SectionLayout};
 use structopt::StructOpt;
 
 fn main() {
@@ -59,8 +70,12 @@ fn main() {
     for elf_path in opt.input {
         // 改成tbf后缀
         let tbf_path = elf_path.with_extension("tbf");
-        // 使用elf包读取elf文件
-        let elffile = elf::File::open_path(&elf_path).expect("Could not open the .elf file.");
+        // Read the whole file in and hand it to the `object` crate, which
+        // understands ELF32/ELF64 in either endianness (and, incidentally,
+        // every other object format it supports).
+        let elf_contents = fs::read(&elf_path).expect("Could not read the .elf file.");
+        let elffile =
+            object::File::parse(&*elf_contents).expect("Could not parse the .elf file.");
 
         if opt.output.clone() == tbf_path.clone() {
             panic!(
@@ -91,6 +106,7 @@ fn main() {
             opt.stack_size,
             opt.app_heap_size,
             opt.kernel_heap_size,
+            opt.auto_reserve,
             opt.protected_region_size,
         )
         .unwrap();
@@ -108,14 +124,99 @@ fn main() {
     }
 }
 
+/// Narrow a 64-bit ELF value (address, size, ...) down to the `u32` the TBF
+/// header format actually stores. On a 32-bit ELF this is always exact; on a
+/// 64-bit ELF (AArch64, RISC-V rv64, ...) we have to check, since TBF's
+/// on-flash layout has no 64-bit fields.
+fn narrow_to_u32(value: u64, what: &str) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} = {:#x} does not fit in the 32-bit TBF header format",
+                what, value
+            ),
+        )
+    })
+}
+
+/// One symbol `reserved_size` can fall back to, and how to read its size.
+///
+/// Linker scripts express a reservation in one of two ways: as a buffer
+/// symbol (e.g. `_stack_buffer`), an array whose `st_size` is the reserved
+/// byte count; or as a scalar constant (e.g. `STACK_SIZE = 2048;`), which
+/// has no size of its own (`st_size` is 0) and instead carries the value in
+/// `st_value`, i.e. `symbol.address()`.
+enum ReservationSymbol {
+    Buffer(&'static str),
+    Scalar(&'static str),
+}
+
+/// Resolve one of the stack/app-heap/kernel-heap reservation sizes.
+///
+/// An explicit `--stack`/`--app-heap`/`--kernel-heap` flag always wins. If
+/// the user didn't pass one and `--auto-reserve` is set, we look for the
+/// first symbol in `symbols` that exists in the ELF's symbol table
+/// (`.symtab`/`.strtab`) and take the reservation from it: its `st_size`
+/// for a `Buffer` symbol, its `st_value` for a `Scalar` one. Otherwise we fall back to
+/// `default`.
+fn reserved_size(
+    input: &object::File,
+    explicit: Option<u32>,
+    auto_reserve: bool,
+    symbols: &[ReservationSymbol],
+    default: u32,
+    what: &str,
+    verbose: bool,
+) -> io::Result<u32> {
+    if let Some(explicit_len) = explicit {
+        if verbose {
+            println!("{} size: {} bytes (from command line flag)", what, explicit_len);
+        }
+        return Ok(explicit_len);
+    }
+
+    if auto_reserve {
+        for reservation_symbol in symbols {
+            let (symbol_name, use_symbol_value) = match reservation_symbol {
+                ReservationSymbol::Buffer(symbol_name) => (*symbol_name, false),
+                ReservationSymbol::Scalar(symbol_name) => (*symbol_name, true),
+            };
+            if let Some(symbol) = input
+                .symbols()
+                .find(|symbol| symbol.name().ok() == Some(symbol_name))
+            {
+                let raw_size = if use_symbol_value {
+                    symbol.address()
+                } else {
+                    symbol.size()
+                };
+                let size = narrow_to_u32(raw_size, what)?;
+                if verbose {
+                    println!(
+                        "{} size: {} bytes (from ELF symbol `{}`)",
+                        what, size, symbol_name
+                    );
+                }
+                return Ok(size);
+            }
+        }
+    }
+
+    if verbose {
+        println!("{} size: {} bytes (default)", what, default);
+    }
+    Ok(default)
+}
+
 /// Convert an ELF file to a TBF (Tock Binary Format) binary file.
 ///
 /// This will place all writeable and executable sections from the ELF file
 /// into a binary and prepend a TBF header to it. For all writeable sections,
-/// if there is a .rel.X section it will be included at the end with a 32 bit
-/// length parameter first.
+/// if there is a .rel.X or .rela.X section it will be included at the end
+/// with a 32 bit length parameter first.
 /// 这会将ELF文件中的所有可写和可执行部分放入二进制文件中，并在其前面添加一个TBF标头。
-///  对于所有可写节，如果有一个.rel.X节，它将在末尾包含32位长度的参数。
+///  对于所有可写节，如果有一个.rel.X或.rela.X节，它将在末尾包含32位长度的参数。
 ///
 /// Assumptions:
 /// - Sections in a segment that is RW and set to be loaded will be in RAM and
@@ -125,44 +226,105 @@ fn main() {
 // -RW段中设置为要加载的段将位于RAM中，并应计入所需的最小RAM中。
 // -可写闪存区域的部分名称中包括.wfr。
 //
-
-// input就是elf的File格式文件
+// input就是用object crate解析出来的elf文件，兼容32/64位、大端/小端
 fn elf_to_tbf<W: Write>(
-    input: &elf::File,
+    input: &object::File,
     output: &mut W,
     package_name: Option<String>,
     verbose: bool,
-    stack_len: u32,
-    app_heap_len: u32,
-    kernel_heap_len: u32,
+    stack_len: Option<u32>,
+    app_heap_len: Option<u32>,
+    kernel_heap_len: Option<u32>,
+    auto_reserve: bool,
     protected_region_size_arg: Option<u32>,
-) -> io::Result<()> {
+) -> io::Result<Layout> {
     let package_name = package_name.unwrap_or_default();
 
+    let stack_len = reserved_size(
+        input,
+        stack_len,
+        auto_reserve,
+        &[
+            ReservationSymbol::Buffer("_stack_buffer"),
+            ReservationSymbol::Scalar("STACK_SIZE"),
+        ],
+        DEFAULT_STACK_SIZE,
+        "Stack",
+        verbose,
+    )?;
+    let app_heap_len = reserved_size(
+        input,
+        app_heap_len,
+        auto_reserve,
+        &[
+            ReservationSymbol::Buffer("_app_heap_buffer"),
+            ReservationSymbol::Scalar("APP_HEAP_SIZE"),
+        ],
+        DEFAULT_APP_HEAP_SIZE,
+        "App heap",
+        verbose,
+    )?;
+    let kernel_heap_len = reserved_size(
+        input,
+        kernel_heap_len,
+        auto_reserve,
+        &[
+            ReservationSymbol::Buffer("_kernel_heap_buffer"),
+            ReservationSymbol::Scalar("KERNEL_HEAP_SIZE"),
+        ],
+        DEFAULT_KERNEL_HEAP_SIZE,
+        "Kernel heap",
+        verbose,
+    )?;
+
     // Get an array of the sections sorted so we place them in the proper order
-    // in the binary.
+    // in the binary. `file_range()` gives us the section's offset in the ELF
+    // file itself, which is what we want to sort on (same as the old
+    // `shdr.offset`).
     // 遍历elf的section并且排序
-    let mut sections_sort: Vec<(usize, usize)> = Vec::new();
-    for (i, section) in input.sections.iter().enumerate() {
-        sections_sort.push((i, section.shdr.offset as usize));
+    let sections: Vec<_> = input.sections().collect();
+    let mut sections_sort: Vec<(usize, u64)> = Vec::new();
+    for (i, section) in sections.iter().enumerate() {
+        let offset = section.file_range().map_or(0, |(offset, _)| offset);
+        sections_sort.push((i, offset));
     }
     sections_sort.sort_by_key(|s| s.1);
 
-    // Keep track of how much RAM this app will need.
+    // Keep track of how much RAM this app will need. Linker scripts often
+    // split RAM across several writeable loadable segments (e.g. separate
+    // `.data` and `.bss` regions, or relro), so rather than taking the size
+    // of a single segment we track the full span, from the lowest to the
+    // highest address, covered by any writeable PT_LOAD segment.
     // 追踪这个app需要的最小的RAM
-    let mut minimum_ram_size: u32 = 0;
+    let mut ram_start: Option<u64> = None;
+    let mut ram_end: u64 = 0;
 
-    // Find the ELF segment for the RAM segment. That will tell us how much
-    // RAM we need to reserve for when those are copied into memory.
+    // `object`'s segments() already only yields loadable segments, so there's
+    // no separate PT_LOAD check to make here.
     // 为了知道需要多少RAM，复制的时候找出elf文件的elf segment, 用elf segment去设置RAM segment。
-    for segment in &input.phdrs {
-        if segment.progtype == elf::types::PT_LOAD
-            && segment.flags.0 == elf::types::PF_W.0 + elf::types::PF_R.0
-        {
-            minimum_ram_size = segment.memsz as u32;
-            break;
+    for segment in input.segments() {
+        if let SegmentFlags::Elf { p_flags } = segment.flags() {
+            if p_flags & object::elf::PF_W != 0 {
+                let segment_start = segment.address();
+                let segment_end = segment_start + segment.size();
+
+                if verbose {
+                    println!(
+                        "  Segment at {:#x}, size {:#x} bytes contributes to minimum RAM.",
+                        segment_start,
+                        segment.size()
+                    );
+                }
+
+                ram_start = Some(ram_start.map_or(segment_start, |start| cmp::min(start, segment_start)));
+                ram_end = cmp::max(ram_end, segment_end);
+            }
         }
     }
+    let mut minimum_ram_size = narrow_to_u32(
+        ram_start.map_or(0, |start| ram_end - start),
+        "minimum RAM size",
+    )?;
     if verbose {
         println!(
             "Min RAM size from sections in ELF: {} bytes",
@@ -185,20 +347,23 @@ fn elf_to_tbf<W: Write>(
     let mut writeable_flash_regions_count = 0;
 
     for s in &sections_sort {
-        let section = &input.sections[s.0];
+        let section = &sections[s.0];
+        let name = section.name().unwrap_or("");
 
         // Count write only sections as writeable flash regions.
         // 计算只能写的sections作为可写的flash寄存器
-        if section.shdr.name.contains(".wfr") && section.shdr.size > 0 {
+        if name.contains(".wfr") && section.size() > 0 {
             writeable_flash_regions_count += 1;
         }
 
         // Check write+alloc sections for possible .rel.X sections.
         // 检查可写可分配的section给.rel.x
-        if section.shdr.flags.0 == elf::types::SHF_WRITE.0 + elf::types::SHF_ALLOC.0 {
-            // This section is also one we might need to include relocation
-            // data for.
-            rel_sections.push(section.shdr.name.clone());
+        if let SectionFlags::Elf { sh_flags } = section.flags() {
+            if sh_flags == u64::from(object::elf::SHF_WRITE | object::elf::SHF_ALLOC) {
+                // This section is also one we might need to include relocation
+                // data for.
+                rel_sections.push(name.to_owned());
+            }
         }
     }
     if verbose {
@@ -208,8 +373,12 @@ fn elf_to_tbf<W: Write>(
         );
     }
 
-    // Keep track of an index of where we are in creating the app binary.
-    let mut binary_index = 0;
+    // ---- Reserve pass -------------------------------------------------
+    //
+    // Walk the ELF file once, in output order, and record the offset and
+    // size of everything that will be written, without writing any bytes
+    // yet. By the end of this pass `layout` and `tbfheader` fully describe
+    // the file we are about to produce.
 
     // Now we can create the first pass TBF header. This is mostly to get the
     // size of the header since we have to fill in some of the offsets later.
@@ -242,7 +411,10 @@ fn elf_to_tbf<W: Write>(
         } else {
             header_length as u32
         };
-    binary_index += protected_region_size as usize;
+
+    // Keep track of an index of where we are in reserving space in the app
+    // binary.
+    let mut binary_index = protected_region_size as usize;
 
     // The init function is where the app will start executing, defined as an
     // offset from the end of protected region at the beginning of the app in
@@ -252,71 +424,94 @@ fn elf_to_tbf<W: Write>(
     // protected region.
     let mut init_fn_offset: u32 = 0;
 
-    // Need a place to put the app sections before we know the true TBF header.
-    let mut binary: Vec<u8> = vec![0; protected_region_size as usize - header_length];
-
     let mut entry_point_found = false;
+    let entry = input.entry();
+
+    // Reserve space for every section we are going to copy into the binary.
+    let mut section_layouts: Vec<SectionLayout> = Vec::new();
 
-    // Iterate the sections in the ELF file and add them to the binary as needed
     for s in &sections_sort {
-        let section = &input.sections[s.0];
+        let section = &sections[s.0];
+        let name = section.name().unwrap_or("");
+        let address = section.address();
+        let size = section.size();
 
         // Determine if this is the section where the entry point is in. If it
         // is, then we need to calculate the correct init_fn_offset.
-        if input.ehdr.entry >= section.shdr.addr
-            && input.ehdr.entry < (section.shdr.addr + section.shdr.size)
-            && (section.shdr.name.find("debug")).is_none()
-        {
+        if entry >= address && entry < (address + size) && name.find("debug").is_none() {
             // panic in case we detect entry point in multiple sections.
             if entry_point_found {
-                panic!("Duplicate entry point in {} section", section.shdr.name);
+                panic!("Duplicate entry point in {} section", name);
             }
             entry_point_found = true;
 
             if verbose {
-                println!("Entry point is in {} section", section.shdr.name);
+                println!("Entry point is in {} section", name);
             }
             // init_fn_offset is specified relative to the end of the TBF
             // header.
-            init_fn_offset = (input.ehdr.entry - section.shdr.addr) as u32
+            init_fn_offset = narrow_to_u32(entry - address, "entry point offset")?
                 + (binary_index - header_length) as u32
         }
 
         // If this is writeable, executable, or allocated, is nonzero length,
-        // and is type `PROGBITS` we want to add it to the binary.
-        if (section.shdr.flags.0
-            & (elf::types::SHF_WRITE.0 + elf::types::SHF_EXECINSTR.0 + elf::types::SHF_ALLOC.0)
-            != 0)
-            && section.shdr.shtype == elf::types::SHT_PROGBITS
-            && section.shdr.size > 0
-        {
+        // and is backed by actual bytes in the file (i.e. would have been
+        // `SHT_PROGBITS` under the old `elf`-crate reading) we want to add it
+        // to the binary. This deliberately excludes allocated-but-special
+        // sections such as `.init_array`/`.fini_array`, `.dynamic`, `.hash`,
+        // or `.dynsym`/`.dynstr`, which are not `SHT_PROGBITS` and were never
+        // copied into the app binary before the migration to `object`.
+        let flags_match = match section.flags() {
+            SectionFlags::Elf { sh_flags } => {
+                sh_flags
+                    & u64::from(
+                        object::elf::SHF_WRITE | object::elf::SHF_EXECINSTR | object::elf::SHF_ALLOC,
+                    )
+                    != 0
+            }
+            _ => false,
+        };
+        let is_progbits_like = matches!(
+            section.kind(),
+            object::SectionKind::Text
+                | object::SectionKind::Data
+                | object::SectionKind::ReadOnlyData
+                | object::SectionKind::ReadOnlyString
+        );
+        if flags_match && is_progbits_like && size > 0 {
+            let section_size = section.data().unwrap_or(&[]).len();
+
             if verbose {
                 println!(
-                    "  Adding {0} section. Offset: {1} ({1:#x}). Length: {2} ({2:#x}) bytes.",
-                    section.shdr.name,
-                    binary_index,
-                    section.data.len(),
+                    "  Reserving {0} section. Offset: {1} ({1:#x}). Length: {2} ({2:#x}) bytes.",
+                    name, binary_index, section_size,
                 );
             }
             if align4needed!(binary_index) != 0 {
                 println!(
                     "Warning! Placing section {} at {:#x}, which is not 4-byte aligned.",
-                    section.shdr.name, binary_index
+                    name, binary_index
                 );
             }
-            binary.extend(&section.data);
 
             // Check if this is a writeable flash region. If so, we need to
             // set the offset and size in the header.
-            if section.shdr.name.contains(".wfr") && section.shdr.size > 0 {
+            if name.contains(".wfr") && size > 0 {
                 tbfheader.set_writeable_flash_region_values(
                     binary_index as u32,
-                    section.shdr.size as u32,
+                    narrow_to_u32(size, "writeable flash region size")?,
                 );
             }
 
+            section_layouts.push(SectionLayout {
+                name: name.to_owned(),
+                section_index: s.0,
+                offset: binary_index,
+                size: section_size,
+            });
+
             // Now increment where we are in the binary.
-            binary_index += section.shdr.size as usize;
+            binary_index += section_size;
         }
     }
 
@@ -324,31 +519,47 @@ fn elf_to_tbf<W: Write>(
     // init_fn_offset.
     tbfheader.set_init_fn_offset(init_fn_offset);
 
-    // Next we have to add in any relocation data.
+    // Next we have to reserve space for any relocation data.
+    //
+    // For each section that might have relocation data, check if a `.relX`
+    // (Elf32_Rel/Elf64_Rel, no addend) or `.relaX` (Elf32_Rela/Elf64_Rela,
+    // explicit addend) section exists and if so include it. Modern RISC-V and
+    // AArch64 toolchains emit the latter, so we can't assume `.rel` anymore.
     let mut relocation_binary: Vec<u8> = Vec::new();
+    let relocation_offset = binary_index;
 
-    // For each section that might have relocation data, check if a .rel.X
-    // section exists and if so include it.
     if verbose {
-        println!("Searching for .rel.X sections to add.");
+        println!("Searching for .rel.X/.rela.X sections to add.");
     }
     for relocation_section_name in &rel_sections {
-        let mut name: String = ".rel".to_owned();
-        name.push_str(relocation_section_name);
+        let rel_name: String = ".rel".to_owned() + relocation_section_name;
+        let rela_name: String = ".rela".to_owned() + relocation_section_name;
 
-        let rel_data = input
-            .sections
-            .iter()
-            .find(|section| section.shdr.name == name)
-            .map_or(&[] as &[u8], |section| section.data.as_ref());
+        let rel_section = input.section_by_name(&rel_name);
+        let rela_section = input.section_by_name(&rela_name);
 
-        relocation_binary.extend(rel_data);
+        let (name, section) = match (rel_section, rela_section) {
+            (Some(_), Some(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Found both {} and {}; expected at most one relocation section per target",
+                        rel_name, rela_name
+                    ),
+                ));
+            }
+            (Some(section), None) => (rel_name, Some(section)),
+            (None, Some(section)) => (rela_name, Some(section)),
+            (None, None) => (rel_name, None),
+        };
+
+        let rel_data = section.and_then(|section| section.data().ok()).unwrap_or(&[]);
 
         if verbose && !rel_data.is_empty() {
             println!(
-                "  Adding {0} section. Offset: {1} ({1:#x}). Length: {2} ({2:#x}) bytes.",
+                "  Reserving {0} section. Offset: {1} ({1:#x}). Length: {2} ({2:#x}) bytes.",
                 name,
-                binary_index + mem::size_of::<u32>() + rel_data.len(),
+                relocation_offset + mem::size_of::<u32>() + relocation_binary.len(),
                 rel_data.len(),
             );
         }
@@ -358,41 +569,581 @@ fn elf_to_tbf<W: Write>(
                 name, binary_index
             );
         }
+
+        relocation_binary.extend(rel_data);
     }
 
-    // Add the relocation data to our total length. Also include the 4 bytes for
-    // the relocation data length.
-    binary_index += relocation_binary.len() + mem::size_of::<u32>();
+    // Reserve the relocation data in our total length. Also include the 4
+    // bytes for the relocation data length.
+    let relocation_size = relocation_binary.len();
+    binary_index += relocation_size + mem::size_of::<u32>();
 
     // That is everything that we are going to include in our app binary. Now
     // we need to pad the binary to a power of 2 in size, and make sure it is
     // at least 512 bytes in size.
-    let post_content_pad = if binary_index.count_ones() > 1 {
+    let pad = if binary_index.count_ones() > 1 {
         let power2len = cmp::max(1 << (32 - (binary_index as u32).leading_zeros()), 512);
         power2len - binary_index
     } else {
         0
     };
-    binary_index += post_content_pad;
+    binary_index += pad;
     let total_size = binary_index;
 
     // Now set the total size of the app in the header.
     tbfheader.set_total_size(total_size as u32);
 
+    let layout = Layout {
+        protected_region_size: protected_region_size as usize,
+        header_size: header_length,
+        sections: section_layouts,
+        relocation_offset,
+        relocation_size,
+        init_fn_offset,
+        pad,
+        total_size,
+    };
+
     if verbose {
         print!("{}", tbfheader);
+        println!("Layout:");
+        print!("{}", layout);
     }
 
-    // Write the header and actual app to a binary file.
+    // ---- Write pass -----------------------------------------------------
+    //
+    // Everything is now reserved; write the bytes out strictly in the order
+    // they appear in `layout`.
+
     output.write_all(tbfheader.generate().unwrap().get_ref())?;
-    output.write_all(binary.as_ref())?;
+    util::do_pad(output, layout.protected_region_size - layout.header_size)?;
+
+    for section_layout in &layout.sections {
+        let section = &sections[section_layout.section_index];
+        output.write_all(section.data().unwrap_or(&[]))?;
+    }
 
-    let rel_data_len: [u8; 4] = (relocation_binary.len() as u32).to_le_bytes();
+    let rel_data_len: [u8; 4] = (layout.relocation_size as u32).to_le_bytes();
     output.write_all(&rel_data_len)?;
     output.write_all(relocation_binary.as_ref())?;
 
     // Pad to get a power of 2 sized flash app.
-    util::do_pad(output, post_content_pad as usize)?;
+    util::do_pad(output, layout.pad)?;
+
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use std::io::Cursor;
+
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_SYMTAB: u32 = 2;
+    const SHT_STRTAB: u32 = 3;
+    const SHF_WRITE: u32 = 0x1;
+    const SHF_ALLOC: u32 = 0x2;
+    const SHF_EXECINSTR: u32 = 0x4;
+    const SHN_ABS: u16 = 0xfff1;
+    const EHDR_SIZE: u32 = 52;
+    const PHDR_SIZE: u32 = 32;
+    const SYM_SIZE: u32 = 16;
+
+    /// Append one `Elf32_Shdr` (section header) to `out`.
+    #[allow(clippy::too_many_arguments)]
+    fn push_shdr(
+        out: &mut Vec<u8>,
+        name: u32,
+        sh_type: u32,
+        flags: u32,
+        addr: u32,
+        offset: u32,
+        size: u32,
+        link: u32,
+        info: u32,
+        entsize: u32,
+    ) {
+        out.extend(&name.to_le_bytes());
+        out.extend(&sh_type.to_le_bytes());
+        out.extend(&flags.to_le_bytes());
+        out.extend(&addr.to_le_bytes());
+        out.extend(&offset.to_le_bytes());
+        out.extend(&size.to_le_bytes());
+        out.extend(&link.to_le_bytes());
+        out.extend(&info.to_le_bytes());
+        out.extend(&4u32.to_le_bytes()); // sh_addralign
+        out.extend(&entsize.to_le_bytes());
+    }
+
+    /// A `PROGBITS` data section to place in a synthetic test ELF.
+    struct TestSection<'a> {
+        name: &'a str,
+        flags: u32,
+        addr: u32,
+        data: &'a [u8],
+    }
+
+    /// A writeable `PT_LOAD` program header to place in a synthetic test ELF.
+    /// `object` reads RAM span directly from the program headers, so these
+    /// don't need any backing file data (`p_filesz` is left at 0).
+    struct TestSegment {
+        p_flags: u32,
+        vaddr: u32,
+        memsz: u32,
+    }
+
+    /// An absolute (`SHN_ABS`) `.symtab` entry to place in a synthetic test
+    /// ELF, paired with a same-named `.strtab` entry.
+    struct TestSymbol<'a> {
+        name: &'a str,
+        value: u32,
+        size: u32,
+    }
+
+    /// Hand-build a minimal little-endian ELF32 file out of the given
+    /// PROGBITS sections, PT_LOAD segments, and absolute symbols. `e_entry`
+    /// is a fixed sentinel outside every section's address range (including
+    /// the zero-address auxiliary sections), so the entry-point scan in
+    /// `elf_to_tbf` never has to pick between two sections that both claim
+    /// it; give data sections distinct, non-overlapping `addr` ranges for
+    /// the same reason.
+    fn build_elf(
+        sections: &[TestSection],
+        segments: &[TestSegment],
+        symbols: &[TestSymbol],
+    ) -> Vec<u8> {
+        let phdr_table_offset = EHDR_SIZE;
+        let phdr_table_size = segments.len() as u32 * PHDR_SIZE;
+
+        let mut section_offsets = Vec::with_capacity(sections.len());
+        let mut cursor = phdr_table_offset + phdr_table_size;
+        for section in sections {
+            section_offsets.push(cursor);
+            cursor += section.data.len() as u32;
+        }
+
+        let has_symbols = !symbols.is_empty();
+        let symtab_offset = cursor;
+        let symtab_size = if has_symbols {
+            (1 + symbols.len() as u32) * SYM_SIZE
+        } else {
+            0
+        };
+        cursor += symtab_size;
 
-    Ok(())
+        let strtab_offset = cursor;
+        let mut strtab_bytes = vec![0u8];
+        let mut symbol_name_offsets = Vec::with_capacity(symbols.len());
+        if has_symbols {
+            for symbol in symbols {
+                symbol_name_offsets.push(strtab_bytes.len() as u32);
+                strtab_bytes.extend(symbol.name.as_bytes());
+                strtab_bytes.push(0);
+            }
+        }
+        cursor += if has_symbols { strtab_bytes.len() as u32 } else { 0 };
+
+        let shstrtab_offset = cursor;
+        let mut shstrtab_bytes = vec![0u8];
+        let mut section_name_offsets = Vec::with_capacity(sections.len());
+        for section in sections {
+            section_name_offsets.push(shstrtab_bytes.len() as u32);
+            shstrtab_bytes.extend(section.name.as_bytes());
+            shstrtab_bytes.push(0);
+        }
+        let symtab_name_offset = shstrtab_bytes.len() as u32;
+        if has_symbols {
+            shstrtab_bytes.extend(b".symtab\0");
+        }
+        let strtab_name_offset = shstrtab_bytes.len() as u32;
+        if has_symbols {
+            shstrtab_bytes.extend(b".strtab\0");
+        }
+        let shstrtab_name_offset = shstrtab_bytes.len() as u32;
+        shstrtab_bytes.extend(b".shstrtab\0");
+        cursor += shstrtab_bytes.len() as u32;
+
+        let shdr_offset = align4!(cursor);
+
+        // 1 (null) + data sections + (symtab, strtab) + shstrtab.
+        let shnum = 1 + sections.len() as u16 + if has_symbols { 2 } else { 0 } + 1;
+        let shstrndx = shnum - 1;
+
+        let mut elf = Vec::new();
+
+        // e_ident
+        elf.extend(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+        elf.extend(&[0u8; 8]);
+        elf.extend(&2u16.to_le_bytes()); // e_type = ET_EXEC
+        elf.extend(&40u16.to_le_bytes()); // e_machine = EM_ARM
+        elf.extend(&1u32.to_le_bytes()); // e_version
+        // e_entry: a sentinel outside every section's address range,
+        // including the zero-address auxiliary sections (.symtab, .strtab,
+        // .shstrtab, .rel*/.rela*) that real linkers never place the entry
+        // point in.
+        elf.extend(&0xffff_ff00_u32.to_le_bytes());
+        elf.extend(
+            &(if segments.is_empty() {
+                0
+            } else {
+                phdr_table_offset
+            })
+            .to_le_bytes(),
+        ); // e_phoff
+        elf.extend(&shdr_offset.to_le_bytes()); // e_shoff
+        elf.extend(&0u32.to_le_bytes()); // e_flags
+        elf.extend(&(EHDR_SIZE as u16).to_le_bytes()); // e_ehsize
+        elf.extend(&(PHDR_SIZE as u16).to_le_bytes()); // e_phentsize
+        elf.extend(&(segments.len() as u16).to_le_bytes()); // e_phnum
+        elf.extend(&40u16.to_le_bytes()); // e_shentsize
+        elf.extend(&shnum.to_le_bytes()); // e_shnum
+        elf.extend(&shstrndx.to_le_bytes()); // e_shstrndx
+        assert_eq!(elf.len() as u32, EHDR_SIZE);
+
+        for segment in segments {
+            elf.extend(&1u32.to_le_bytes()); // p_type = PT_LOAD
+            elf.extend(&0u32.to_le_bytes()); // p_offset
+            elf.extend(&segment.vaddr.to_le_bytes()); // p_vaddr
+            elf.extend(&segment.vaddr.to_le_bytes()); // p_paddr
+            elf.extend(&0u32.to_le_bytes()); // p_filesz
+            elf.extend(&segment.memsz.to_le_bytes()); // p_memsz
+            elf.extend(&segment.p_flags.to_le_bytes()); // p_flags
+            elf.extend(&4u32.to_le_bytes()); // p_align
+        }
+
+        for section in sections {
+            elf.extend(section.data);
+        }
+
+        if has_symbols {
+            elf.extend(&[0u8; SYM_SIZE as usize]); // STN_UNDEF
+            for (symbol, name_offset) in symbols.iter().zip(&symbol_name_offsets) {
+                elf.extend(&name_offset.to_le_bytes()); // st_name
+                elf.extend(&symbol.value.to_le_bytes()); // st_value
+                elf.extend(&symbol.size.to_le_bytes()); // st_size
+                elf.push(0x11); // st_info = STB_GLOBAL << 4 | STT_OBJECT
+                elf.push(0); // st_other
+                elf.extend(&SHN_ABS.to_le_bytes()); // st_shndx
+            }
+            elf.extend(&strtab_bytes);
+        }
+
+        elf.extend(&shstrtab_bytes);
+        while (elf.len() as u32) < shdr_offset {
+            elf.push(0);
+        }
+
+        push_shdr(&mut elf, 0, 0, 0, 0, 0, 0, 0, 0, 0); // SHN_UNDEF
+        for ((section, &name_offset), &offset) in
+            sections.iter().zip(&section_name_offsets).zip(&section_offsets)
+        {
+            push_shdr(
+                &mut elf,
+                name_offset,
+                SHT_PROGBITS,
+                section.flags,
+                section.addr,
+                offset,
+                section.data.len() as u32,
+                0,
+                0,
+                0,
+            );
+        }
+        if has_symbols {
+            // `.symtab`'s sh_link points at its string table; sh_info is the
+            // index of the first non-local (here: only) symbol, i.e. 1 past
+            // the mandatory null entry.
+            let strtab_index = 1 + sections.len() as u32 + 1;
+            push_shdr(
+                &mut elf,
+                symtab_name_offset,
+                SHT_SYMTAB,
+                0,
+                0,
+                symtab_offset,
+                symtab_size,
+                strtab_index,
+                1,
+                SYM_SIZE,
+            );
+            push_shdr(
+                &mut elf,
+                strtab_name_offset,
+                SHT_STRTAB,
+                0,
+                0,
+                strtab_offset,
+                strtab_bytes.len() as u32,
+                0,
+                0,
+                0,
+            );
+        }
+        push_shdr(
+            &mut elf,
+            shstrtab_name_offset,
+            SHT_STRTAB,
+            0,
+            0,
+            shstrtab_offset,
+            shstrtab_bytes.len() as u32,
+            0,
+            0,
+            0,
+        );
+
+        elf
+    }
+
+    #[test]
+    fn reserve_and_write_produce_matching_layout() {
+        let elf_bytes = build_elf(
+            &[
+                TestSection {
+                    name: ".text",
+                    flags: SHF_ALLOC | SHF_EXECINSTR,
+                    addr: 0x8000,
+                    data: &[0xff; 16],
+                },
+                TestSection {
+                    name: ".rodata",
+                    flags: SHF_ALLOC,
+                    addr: 0x9000,
+                    data: &[0xaa; 8],
+                },
+            ],
+            &[],
+            &[],
+        );
+        let elf = object::File::parse(&*elf_bytes).expect("failed to parse synthetic test ELF");
+
+        let mut out = Cursor::new(Vec::new());
+        let layout = elf_to_tbf(
+            &elf,
+            &mut out,
+            Some("test_app".to_string()),
+            false,
+            Some(0),
+            Some(0),
+            Some(0),
+            false,
+            None,
+        )
+        .expect("elf_to_tbf should succeed on a well-formed synthetic ELF");
+
+        // The protected region is exactly the generated header, since no
+        // fixed protected-region-size was requested.
+        assert_eq!(layout.protected_region_size, layout.header_size);
+
+        // Both sections should have been reserved, in file order, directly
+        // after the protected region, each 4-byte aligned.
+        assert_eq!(layout.sections.len(), 2);
+        assert_eq!(layout.sections[0].offset, layout.protected_region_size);
+        assert_eq!(layout.sections[0].size, 16);
+        assert_eq!(layout.sections[1].offset, layout.sections[0].offset + 16);
+        assert_eq!(layout.sections[1].size, 8);
+
+        // No relocation sections were present, so the relocation blob is
+        // empty but its 4-byte length prefix is still reserved right after
+        // the last section.
+        assert_eq!(layout.relocation_size, 0);
+        assert_eq!(
+            layout.relocation_offset,
+            layout.sections[1].offset + layout.sections[1].size
+        );
+
+        // The whole file is padded out to the next power of two, at least
+        // 512 bytes, and what got written matches that size exactly.
+        assert!(layout.total_size.is_power_of_two());
+        assert!(layout.total_size >= 512);
+        assert_eq!(out.get_ref().len(), layout.total_size);
+    }
+
+    /// The offset, in a written TBF binary, of the `minimum_ram_size` field
+    /// of the main TLV entry. See the byte layout `TbfHeader::generate`
+    /// produces in `header.rs`: 16 bytes of base header, then a 4-byte main
+    /// TLV type/length pair, then `init_fn_offset` and `protected_size`
+    /// (4 bytes each) ahead of `minimum_ram_size`.
+    const MINIMUM_RAM_SIZE_OFFSET: usize = 16 + 4 + 4 + 4;
+
+    fn minimum_ram_size_from_header(out: &[u8]) -> u32 {
+        u32::from_le_bytes(
+            out[MINIMUM_RAM_SIZE_OFFSET..MINIMUM_RAM_SIZE_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn minimum_ram_spans_every_writeable_segment() {
+        // Two disjoint writeable PT_LOAD segments, as a linker script that
+        // splits .data from .bss might emit. The minimum RAM size must cover
+        // the full span from the lowest to the highest address, not just
+        // one segment's own size.
+        let elf_bytes = build_elf(
+            &[],
+            &[
+                TestSegment {
+                    p_flags: object::elf::PF_W,
+                    vaddr: 0x2000,
+                    memsz: 0x10,
+                },
+                TestSegment {
+                    p_flags: object::elf::PF_W,
+                    vaddr: 0x3000,
+                    memsz: 0x40,
+                },
+            ],
+            &[],
+        );
+        let elf = object::File::parse(&*elf_bytes).expect("failed to parse synthetic test ELF");
+
+        let mut out = Cursor::new(Vec::new());
+        elf_to_tbf(
+            &elf,
+            &mut out,
+            Some("test_app".to_string()),
+            false,
+            Some(0),
+            Some(0),
+            Some(0),
+            false,
+            None,
+        )
+        .expect("elf_to_tbf should succeed on a well-formed synthetic ELF");
+
+        // Span from 0x2000 to 0x3040, i.e. 0x1040, not just one segment.
+        assert_eq!(minimum_ram_size_from_header(out.get_ref()), 0x1040);
+    }
+
+    #[test]
+    fn auto_reserve_reads_scalar_symbol_value_not_size() {
+        // STACK_SIZE is a linker-script scalar (`STACK_SIZE = 0x601;`), so
+        // its st_size is 0 and the real value is st_value.
+        let elf_bytes = build_elf(
+            &[],
+            &[],
+            &[TestSymbol {
+                name: "STACK_SIZE",
+                value: 0x601,
+                size: 0,
+            }],
+        );
+        let elf = object::File::parse(&*elf_bytes).expect("failed to parse synthetic test ELF");
+
+        let mut out = Cursor::new(Vec::new());
+        elf_to_tbf(
+            &elf,
+            &mut out,
+            Some("test_app".to_string()),
+            false,
+            None,
+            Some(0),
+            Some(0),
+            true,
+            None,
+        )
+        .expect("elf_to_tbf should succeed on a well-formed synthetic ELF");
+
+        // Stack reservation is 8-byte aligned; app/kernel heap reservations
+        // are 0, so the whole minimum RAM size is align8!(0x601) = 0x608.
+        assert_eq!(minimum_ram_size_from_header(out.get_ref()), 0x608);
+    }
+
+    #[test]
+    fn rela_section_is_included_as_relocation_data() {
+        // A writeable+alloc `.data.test` section with a matching `.rela`
+        // (explicit-addend) relocation section, as RISC-V/AArch64
+        // toolchains emit instead of `.rel`.
+        let rela_data = [0x11_u8; 24]; // 2 Elf32_Rela entries, contents unchecked.
+        let elf_bytes = build_elf(
+            &[
+                TestSection {
+                    name: ".data.test",
+                    flags: SHF_WRITE | SHF_ALLOC,
+                    addr: 0x8000,
+                    data: &[0x42; 4],
+                },
+                TestSection {
+                    name: ".rela.data.test",
+                    flags: 0,
+                    addr: 0,
+                    data: &rela_data,
+                },
+            ],
+            &[],
+            &[],
+        );
+        let elf = object::File::parse(&*elf_bytes).expect("failed to parse synthetic test ELF");
+
+        let mut out = Cursor::new(Vec::new());
+        let layout = elf_to_tbf(
+            &elf,
+            &mut out,
+            Some("test_app".to_string()),
+            false,
+            Some(0),
+            Some(0),
+            Some(0),
+            false,
+            None,
+        )
+        .expect("elf_to_tbf should succeed on a well-formed synthetic ELF");
+
+        assert_eq!(layout.relocation_size, rela_data.len());
+        let reloc_data_start = layout.relocation_offset + mem::size_of::<u32>();
+        assert_eq!(
+            &out.get_ref()[reloc_data_start..reloc_data_start + rela_data.len()],
+            &rela_data[..]
+        );
+    }
+
+    #[test]
+    fn both_rel_and_rela_sections_is_an_error() {
+        // A single writeable+alloc section with *both* a `.rel` and a
+        // `.rela` relocation section is ambiguous (which one is current?)
+        // and must be rejected rather than silently picking one.
+        let elf_bytes = build_elf(
+            &[
+                TestSection {
+                    name: ".data.test",
+                    flags: SHF_WRITE | SHF_ALLOC,
+                    addr: 0x8000,
+                    data: &[0x42; 4],
+                },
+                TestSection {
+                    name: ".rel.data.test",
+                    flags: 0,
+                    addr: 0,
+                    data: &[0x11; 8],
+                },
+                TestSection {
+                    name: ".rela.data.test",
+                    flags: 0,
+                    addr: 0,
+                    data: &[0x11; 12],
+                },
+            ],
+            &[],
+            &[],
+        );
+        let elf = object::File::parse(&*elf_bytes).expect("failed to parse synthetic test ELF");
+
+        let mut out = Cursor::new(Vec::new());
+        let result = elf_to_tbf(
+            &elf,
+            &mut out,
+            Some("test_app".to_string()),
+            false,
+            Some(0),
+            Some(0),
+            Some(0),
+            false,
+            None,
+        );
+
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,40 @@
+//! Small helpers shared across the crate: alignment macros and padding.
+
+use std::io;
+use std::io::Write;
+
+/// Round `$e` up to the next multiple of 4. Generic over the integer type of
+/// `$e` (callers use both `u32` sizes and `usize` byte offsets).
+macro_rules! align4 {
+    ($e:expr) => {
+        ($e + 3) & !3
+    };
+}
+
+/// Round `$e` up to the next multiple of 8. Generic over the integer type of
+/// `$e` (its caller uses it on a `u32` size).
+macro_rules! align8 {
+    ($e:expr) => {
+        ($e + 7) & !7
+    };
+}
+
+/// How many bytes short of 4-byte alignment `$e` currently is. Zero means
+/// `$e` is already aligned.
+macro_rules! align4needed {
+    ($e:expr) => {
+        (4 - ($e as usize) % 4) % 4
+    };
+}
+
+/// Write `pad_len` zero bytes to `output`.
+pub fn do_pad<W: Write>(output: &mut W, pad_len: usize) -> io::Result<()> {
+    let zero_buf = [0_u8; 512];
+    let mut remaining = pad_len;
+    while remaining > 0 {
+        let amount = std::cmp::min(remaining, zero_buf.len());
+        output.write_all(&zero_buf[..amount])?;
+        remaining -= amount;
+    }
+    Ok(())
+}